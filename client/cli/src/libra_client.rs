@@ -2,14 +2,17 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::AccountData;
-use admission_control_proto::proto::AdmissionControlClientBlocking;
-use anyhow::{bail, ensure, format_err, Result};
+use anyhow::format_err;
+use async_stream::try_stream;
+use futures::stream::Stream;
+use libra_crypto::{hash::CryptoHash, HashValue};
 use libra_logger::prelude::*;
 use libra_types::{
     access_path::AccessPath,
     account_address::AccountAddress,
     account_config::AccountResource,
     account_state_blob::{AccountStateBlob, AccountStateWithProof},
+    chain_id::ChainId,
     contract_event::{ContractEvent, EventWithProof},
     crypto_proxies::LedgerInfoWithSignatures,
     get_with_proof::{
@@ -21,30 +24,119 @@ use libra_types::{
 };
 use rand::Rng;
 use reqwest::blocking::Client;
-use std::{convert::TryFrom, time::Duration};
+use std::{
+    convert::TryFrom,
+    thread,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
 
 const JSON_RPC_TIMEOUT_MS: u64 = 5_000;
-const MAX_GRPC_RETRY_COUNT: u64 = 2;
+const MAX_RETRY_COUNT: u64 = 2;
+/// How long to wait before reconnecting a dropped subscription.
+const SUBSCRIPTION_RECONNECT_DELAY_MS: u64 = 1_000;
+/// Initial delay between `submit_and_confirm_transaction` confirmation polls; doubles after
+/// every poll that finds nothing, up to [`SUBMIT_POLL_MAX_BACKOFF_MS`].
+const SUBMIT_POLL_INITIAL_BACKOFF_MS: u64 = 100;
+const SUBMIT_POLL_MAX_BACKOFF_MS: u64 = 2_000;
+
+type SubscriptionSocket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// The result type returned by [`JsonRpcClient`] and [`LibraClient`].
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors raised by [`JsonRpcClient`] and [`LibraClient`], classified by whether retrying the
+/// same request could plausibly succeed. Callers (in particular `send_with_retry` and
+/// `need_to_retry`) consult [`Error::is_retriable`] instead of retrying on any error, so we
+/// never re-submit a transaction that failed for a reason that will just happen again.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The server responded with a non-2xx HTTP status.
+    #[error("server returned HTTP status {0}")]
+    HttpStatus(u16),
+    /// The request did not complete before the client-side timeout elapsed.
+    #[error("request timed out")]
+    Timeout,
+    /// The underlying HTTP or WebSocket connection couldn't even get a response (e.g. connection
+    /// refused or reset), as opposed to a response we received but couldn't parse. Transient by
+    /// nature.
+    #[error("transport error: {0}")]
+    Transport(#[source] anyhow::Error),
+    /// The server's JSON RPC response carried an `error` payload.
+    #[error("JSON RPC error {code}: {message}")]
+    JsonRpcError { code: i64, message: String },
+    /// The response we verified against our `trusted_state` reported a version older than
+    /// what we've already verified, e.g. because a stale replica served the request.
+    #[error("received a response older than our last verified state")]
+    StaleResponse,
+    /// The response failed ledger-proof verification.
+    #[error("proof verification failed: {0}")]
+    InvalidProof(#[source] anyhow::Error),
+    /// The server's chain id doesn't match the one we pinned on first contact (or were
+    /// constructed with), i.e. we're talking to the wrong network entirely. Retrying against
+    /// the same host will just hit the same mismatch, so this is fatal.
+    #[error("server chain id {actual} does not match pinned chain id {expected}")]
+    ChainIdMismatch { expected: u8, actual: u8 },
+    /// While polling for confirmation, the sender's sequence number advanced past the
+    /// submitted transaction's without it ever showing up, i.e. it was dropped (e.g. superseded
+    /// by another transaction with the same sequence number). Retrying the same submission
+    /// would just be rejected for the same reason.
+    #[error("transaction was dropped: sequence number {0} was superseded before it committed")]
+    TransactionDropped(u64),
+    /// The response could not be parsed into the shape we expected.
+    #[error("failed to decode response: {0}")]
+    Decode(#[source] anyhow::Error),
+}
+
+impl Error {
+    /// Returns `true` if the same request might succeed on a retry: transient server errors,
+    /// timeouts, and responses that merely lag behind our trusted state. Returns `false` for
+    /// errors that will deterministically recur, like a malformed proof or an undecodable
+    /// payload.
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            Error::HttpStatus(status) => (500..600).contains(status),
+            Error::Timeout | Error::Transport(_) | Error::StaleResponse => true,
+            Error::JsonRpcError { .. }
+            | Error::InvalidProof(_)
+            | Error::ChainIdMismatch { .. }
+            | Error::TransactionDropped(_)
+            | Error::Decode(_) => false,
+        }
+    }
+}
 
-/// A client connection to an AdmissionControl (AC) service. `LibraClient` also
-/// handles verifying the server's responses, retrying on non-fatal failures, and
-/// ratcheting our latest verified state, which includes the latest verified
-/// version and latest verified epoch change ledger info.
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Error::Timeout
+        } else if let Some(status) = err.status() {
+            Error::HttpStatus(status.as_u16())
+        } else {
+            // No status means we never got a response at all, e.g. connection refused/reset or
+            // a DNS failure — a transport problem, not a malformed response, so it's worth
+            // retrying.
+            Error::Transport(err.into())
+        }
+    }
+}
+
+/// A client connection to a validator's JSON RPC service. `LibraClient` also handles
+/// verifying the server's responses, retrying on non-fatal failures, and ratcheting our
+/// latest verified state, which includes the latest verified version and latest verified
+/// epoch change ledger info.
 ///
 /// ### Note
 ///
 /// `LibraClient` will reject out-of-date responses. For example, this can happen if
 ///
-/// 1. We make a request to the remote AC service.
+/// 1. We make a request to the remote validator.
 /// 2. The remote service crashes and it forgets the most recent state or an
 ///    out-of-date replica takes its place.
-/// 3. We make another request to the remote AC service. In this case, the remote
-///    AC will be behind us and we will reject their response as stale.
+/// 3. We make another request to the remote validator. In this case, the remote
+///    will be behind us and we will reject their response as stale.
 pub struct LibraClient {
-    /// The client connection to an AdmissionControl service. We will only connect
-    /// when the first request is made.
-    /// TODO deprecate this completely once migration to JSON RPC is complete
-    client: AdmissionControlClientBlocking,
     json_rpc_client: JsonRpcClient,
     /// The latest verified chain state.
     trusted_state: TrustedState,
@@ -52,6 +144,10 @@ pub struct LibraClient {
     /// about our local [`Waypoint`] and have not yet ratcheted to the remote's
     /// latest state.
     latest_epoch_change_li: Option<LedgerInfoWithSignatures>,
+    /// The chain id we're pinned to. `None` until either supplied to [`LibraClient::new`] or
+    /// learned from the first JSON RPC response; every response after that is rejected if it
+    /// reports a different chain id, so we never silently talk to the wrong network.
+    chain_id: Option<ChainId>,
 }
 
 pub struct JsonRpcClient {
@@ -67,95 +163,327 @@ impl JsonRpcClient {
         Self { client, addr }
     }
 
+    /// The `ws://` equivalent of this client's JSON RPC address, used for streaming
+    /// subscriptions.
+    fn ws_addr(&self) -> String {
+        format!("ws{}", &self.addr["http".len()..])
+    }
+
     /// Sends JSON request `request`, performs basic checks on the payload, and returns Ok(`result`),
     /// where `result` is the payload under the key "result" in the JSON RPC response
     /// If there is an error payload in the JSON RPC response, throw an Err with message describing the error
     /// payload
+    ///
+    /// `pinned_chain_id` pins to the chain id reported by the first response that flows through
+    /// here and rejects any later response reporting a different one. Passing a fresh
+    /// `&mut None` accepts whatever chain id this one response reports; a response that reports
+    /// no chain id at all is still rejected.
     pub fn send_libra_request(
         &mut self,
         method: String,
         params: Vec<String>,
+        pinned_chain_id: &mut Option<ChainId>,
     ) -> Result<serde_json::Value> {
         let id: u64 = rand::thread_rng().gen();
-        let request = serde_json::json!({
+        let mut request = serde_json::json!({
             "jsonrpc": "2.0",
             "method": method,
             "params": params,
             "id": id,
         });
+        if let Some(chain_id) = pinned_chain_id {
+            request["chain_id"] = serde_json::json!(chain_id.id());
+        }
 
-        let response = self
-            .send_with_retry(request)?
-            .error_for_status()
-            .map_err(|e| format_err!("Server returned error: {:?}", e))?;
+        let response = self.send_with_retry(&request)?;
 
         // check payload
-        let data: serde_json::Value = response.json()?;
+        let data: serde_json::Value = response.json().map_err(|e| Error::Decode(e.into()))?;
 
+        check_chain_id(&data, pinned_chain_id)?;
+        Self::parse_single_response(&data, id)
+    }
+
+    /// Sends a batch of JSON RPC requests as a single HTTP round trip. Each element of
+    /// `calls` is a (method, params) pair; every call is assigned its own random `id` so
+    /// the response array can be demultiplexed back to the right caller even if the server
+    /// returns the responses out of order. Unlike `send_libra_request`, a single call
+    /// failing (a JSON RPC error, a missing id, ...) does not fail the whole batch: each
+    /// slot in the returned `Vec` reports its own call's success or failure.
+    ///
+    /// See [`send_libra_request`](Self::send_libra_request) for `pinned_chain_id`.
+    pub fn send_libra_batch(
+        &mut self,
+        calls: Vec<(String, Vec<String>)>,
+        pinned_chain_id: &mut Option<ChainId>,
+    ) -> Result<Vec<Result<serde_json::Value>>> {
+        if calls.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let ids: Vec<u64> = calls.iter().map(|_| rand::thread_rng().gen()).collect();
+        let mut batch_request: Vec<serde_json::Value> = ids
+            .iter()
+            .zip(calls.into_iter())
+            .map(|(id, (method, params))| {
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "method": method,
+                    "params": params,
+                    "id": id,
+                })
+            })
+            .collect();
+        if let Some(chain_id) = pinned_chain_id {
+            for request in batch_request.iter_mut() {
+                request["chain_id"] = serde_json::json!(chain_id.id());
+            }
+        }
+
+        let response = self.send_with_retry(&serde_json::Value::Array(batch_request))?;
+
+        let data: serde_json::Value = response.json().map_err(|e| Error::Decode(e.into()))?;
+        let responses = data.as_array().ok_or_else(|| {
+            Error::Decode(format_err!(
+                "expected a JSON array for batch response, got: {:?}",
+                data
+            ))
+        })?;
+
+        Ok(demux_batch_responses(&ids, responses)
+            .into_iter()
+            .map(|result| {
+                result.and_then(|(entry, id)| {
+                    check_chain_id(entry, pinned_chain_id)?;
+                    Self::parse_single_response(entry, id)
+                })
+            })
+            .collect())
+    }
+
+    /// Runs the common JSON RPC response checks (protocol version, id match, error payload)
+    /// and returns the `result` payload on success.
+    fn parse_single_response(
+        data: &serde_json::Value,
+        expected_id: u64,
+    ) -> Result<serde_json::Value> {
         // check JSON RPC protocol
         let json_rpc_protocol = data.get("jsonrpc");
-        ensure!(
-            json_rpc_protocol == Some(&serde_json::Value::String("2.0".to_string())),
-            "JSON RPC response with incorrect protocol: {:?}",
-            json_rpc_protocol
-        );
+        if json_rpc_protocol != Some(&serde_json::Value::String("2.0".to_string())) {
+            return Err(Error::Decode(format_err!(
+                "JSON RPC response with incorrect protocol: {:?}",
+                json_rpc_protocol
+            )));
+        }
 
         // check ID
         let response_id = data.get("id");
-        ensure!(
-            response_id == Some(&serde_json::json!(id)),
-            "JSON RPC response ID {:?} does not match request ID {}",
-            response_id,
-            id
-        );
+        if response_id != Some(&serde_json::json!(expected_id)) {
+            return Err(Error::Decode(format_err!(
+                "JSON RPC response ID {:?} does not match request ID {}",
+                response_id,
+                expected_id
+            )));
+        }
 
         if let Some(error) = data.get("error") {
-            bail!("Error in JSON RPC response: {:?}", error);
+            let code = error
+                .get("code")
+                .and_then(serde_json::Value::as_i64)
+                .unwrap_or(0);
+            let message = error
+                .get("message")
+                .and_then(serde_json::Value::as_str)
+                .unwrap_or("")
+                .to_string();
+            return Err(Error::JsonRpcError { code, message });
         }
 
         if let Some(result) = data.get("result") {
             Ok(result.clone())
         } else {
-            bail!("Received JSON RPC response with no result payload");
+            Err(Error::Decode(format_err!(
+                "received JSON RPC response with no result payload"
+            )))
         }
     }
 
-    // send with retry
+    // send with retry, only retrying errors that `is_retriable`
     pub fn send_with_retry(
         &mut self,
-        request: serde_json::Value,
+        request: &serde_json::Value,
     ) -> Result<reqwest::blocking::Response> {
-        let mut response = self.send(&request);
+        let mut response = self.send(request);
         let mut try_cnt = 0;
 
-        // retry if send fails
-        while try_cnt < MAX_GRPC_RETRY_COUNT && response.is_err() {
-            response = self.send(&request);
+        while try_cnt < MAX_RETRY_COUNT && matches!(&response, Err(error) if error.is_retriable()) {
+            response = self.send(request);
             try_cnt += 1;
         }
         response
     }
 
+    /// Sends `request` and turns a non-2xx HTTP status into `Err(Error::HttpStatus)` here
+    /// (rather than leaving it to the caller), so `send_with_retry`'s retry loop actually sees
+    /// and honors `is_retriable`'s 5xx classification instead of always getting `Ok`.
     fn send(&mut self, request: &serde_json::Value) -> Result<reqwest::blocking::Response> {
-        self.client
+        let response = self
+            .client
             .post(&self.addr)
             .json(request)
             .timeout(Duration::from_millis(JSON_RPC_TIMEOUT_MS))
-            .send()
-            .map_err(Into::into)
+            .send()?;
+
+        if !response.status().is_success() {
+            return Err(Error::HttpStatus(response.status().as_u16()));
+        }
+        Ok(response)
+    }
+}
+
+/// Verifies `resp` (the response to `req`) against `trusted_state` and returns the ratcheted
+/// trusted state, plus the new epoch-change ledger info if an epoch boundary was crossed.
+/// Shared by the blocking [`LibraClient::get_with_proof`] and its `async` counterpart so
+/// verification behavior stays identical between the two.
+fn ratchet_trusted_state(
+    trusted_state: &TrustedState,
+    req: &UpdateToLatestLedgerRequest,
+    resp: &UpdateToLatestLedgerResponse,
+) -> Result<(TrustedState, Option<LedgerInfoWithSignatures>)> {
+    let resp_version = resp.ledger_info_with_sigs.ledger_info().version();
+    if resp_version < trusted_state.latest_version() {
+        // The remote fell behind us, e.g. because it crashed and an out-of-date replica
+        // took its place; retrying against a (hopefully) caught-up replica is safe.
+        return Err(Error::StaleResponse);
+    }
+
+    match resp
+        .verify(trusted_state, req)
+        .map_err(Error::InvalidProof)?
+    {
+        TrustedStateChange::Epoch {
+            new_state,
+            latest_epoch_change_li,
+            latest_validator_set,
+            ..
+        } => {
+            info!(
+                "Verified epoch change to epoch: {}, validator set: [{}]",
+                latest_epoch_change_li.ledger_info().epoch(),
+                latest_validator_set
+            );
+            Ok((new_state, Some(latest_epoch_change_li.clone())))
+        }
+        TrustedStateChange::Version { new_state, .. } => Ok((new_state, None)),
+    }
+}
+
+/// Maps a [`RequestItem`] to the JSON RPC method that serves it, e.g.
+/// `get_account_state_with_proof`, `get_account_transaction`, `get_transactions`, and
+/// `get_events`. Every call still ships the full LCS-encoded [`UpdateToLatestLedgerRequest`] as
+/// its single param and gets back an LCS-encoded [`UpdateToLatestLedgerResponse`], so
+/// `ratchet_trusted_state` can verify it exactly as it did against the old gRPC response.
+fn request_item_method(item: &RequestItem) -> &'static str {
+    match item {
+        RequestItem::GetAccountState { .. } => "get_account_state_with_proof",
+        RequestItem::GetAccountTransactionBySequenceNumber { .. } => "get_account_transaction",
+        RequestItem::GetTransactions { .. } => "get_transactions",
+        RequestItem::GetEventsByEventAccessPath { .. } => "get_events",
     }
 }
 
+/// Checks that `committed` — the transaction `get_txn_by_acc_seq` found at the sequence number
+/// we polled for — is actually the one we submitted (`submitted_hash`). `get_txn_by_acc_seq`
+/// returns whatever transaction occupies that slot, which may be a different one that
+/// superseded ours, so a hash mismatch is reported as [`Error::TransactionDropped`] rather than
+/// false success.
+fn check_submitted_transaction(
+    committed: &Transaction,
+    submitted_hash: HashValue,
+    sequence_number: u64,
+) -> Result<()> {
+    match committed {
+        Transaction::UserTransaction(txn) if txn.hash() == submitted_hash => Ok(()),
+        _ => Err(Error::TransactionDropped(sequence_number)),
+    }
+}
+
+/// Indexes `responses` by id and pairs each of `ids` back up with its entry, so the response
+/// array can be demultiplexed back to the right caller regardless of the order the server
+/// returned them in. An id with no matching entry, or with more than one, is reported as an
+/// `Err` for that id rather than silently dropped or resolved to whichever entry happened to
+/// arrive first or last (which could hand one caller's response to another).
+fn demux_batch_responses<'a>(
+    ids: &[u64],
+    responses: &'a [serde_json::Value],
+) -> Vec<Result<(&'a serde_json::Value, u64)>> {
+    let mut by_id = std::collections::HashMap::new();
+    let mut duplicate_ids = std::collections::HashSet::new();
+    for entry in responses {
+        if let Some(id) = entry.get("id").and_then(serde_json::Value::as_u64) {
+            if by_id.insert(id, entry).is_some() {
+                duplicate_ids.insert(id);
+            }
+        }
+    }
+
+    ids.iter()
+        .map(|&id| {
+            if duplicate_ids.contains(&id) {
+                return Err(Error::Decode(format_err!(
+                    "server returned more than one response with id {} in batch response",
+                    id
+                )));
+            }
+            by_id.remove(&id).map(|entry| (entry, id)).ok_or_else(|| {
+                Error::Decode(format_err!(
+                    "no response with id {} found in batch response",
+                    id
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Checks `data`'s reported `chain_id` against `pinned_chain_id`, pinning it on the first call
+/// (when `pinned_chain_id` is `None`) and rejecting any later response that reports a different
+/// one with a fatal [`Error::ChainIdMismatch`]. Guards against a client configured for one
+/// network silently talking to another.
+fn check_chain_id(data: &serde_json::Value, pinned_chain_id: &mut Option<ChainId>) -> Result<()> {
+    let reported = data
+        .get("chain_id")
+        .and_then(serde_json::Value::as_u64)
+        .ok_or_else(|| {
+            Error::Decode(format_err!(
+                "JSON RPC response missing chain_id: {:?}",
+                data
+            ))
+        })?;
+    let reported = ChainId::new(reported as u8);
+
+    match pinned_chain_id {
+        Some(expected) if *expected != reported => {
+            return Err(Error::ChainIdMismatch {
+                expected: expected.id(),
+                actual: reported.id(),
+            })
+        }
+        Some(_) => (),
+        None => *pinned_chain_id = Some(reported),
+    }
+    Ok(())
+}
+
 impl LibraClient {
-    /// Construct a new Client instance.
+    /// Construct a new Client instance. If `chain_id` is `None`, the client pins to whatever
+    /// chain id the server reports on its first response instead of checking one up front.
     // TODO(philiphayes/dmitrip): Waypoint should not be optional
     pub fn new(
         host: &str,
-        ac_port: u16,
         json_rpc_port: u16,
         waypoint: Option<Waypoint>,
+        chain_id: Option<ChainId>,
     ) -> Result<Self> {
-        let client = AdmissionControlClientBlocking::new(host, ac_port);
         // If waypoint is present, use it for initial verification, otherwise the initial
         // verification is essentially empty.
         let initial_trusted_state = match waypoint {
@@ -164,10 +492,10 @@ impl LibraClient {
         };
         let json_rpc_client = JsonRpcClient::new(host, json_rpc_port);
         Ok(LibraClient {
-            client,
             json_rpc_client,
             trusted_state: initial_trusted_state,
             latest_epoch_change_li: None,
+            chain_id,
         })
     }
 
@@ -182,25 +510,61 @@ impl LibraClient {
         let payload = hex::encode(lcs::to_bytes(&transaction).unwrap());
         let params = vec![payload];
 
-        match self
-            .json_rpc_client
-            .send_libra_request("submit".to_string(), params)
-        {
-            Ok(result) => {
-                ensure!(
-                    result == serde_json::Value::Null,
-                    "Received unexpected result payload from txn submission: {:?}",
-                    result
-                );
-                if let Some(sender_account) = sender_account_opt {
-                    // Bump up sequence_number if transaction is accepted.
-                    sender_account.sequence_number += 1;
-                }
-                Ok(())
+        let result = self.json_rpc_client.send_libra_request(
+            "submit".to_string(),
+            params,
+            &mut self.chain_id,
+        )?;
+        if result != serde_json::Value::Null {
+            return Err(Error::Decode(format_err!(
+                "received unexpected result payload from txn submission: {:?}",
+                result
+            )));
+        }
+        if let Some(sender_account) = sender_account_opt {
+            // Bump up sequence_number if transaction is accepted.
+            sender_account.sequence_number += 1;
+        }
+        Ok(())
+    }
+
+    /// Submits `transaction`, then polls for its commit by the sender's sequence number,
+    /// backing off exponentially between polls, until either our transaction is found
+    /// committed, `sender`'s sequence number advances past it without our transaction ever
+    /// showing up (it was dropped, e.g. superseded by another transaction with the same
+    /// sequence number), or `timeout` elapses.
+    pub fn submit_and_confirm_transaction(
+        &mut self,
+        sender: &mut AccountData,
+        transaction: SignedTransaction,
+        timeout: Duration,
+    ) -> Result<Transaction> {
+        let address = sender.address;
+        let sequence_number = sender.sequence_number;
+        let submitted_hash = transaction.hash();
+
+        self.submit_transaction(Some(sender), transaction)?;
+
+        let deadline = Instant::now() + timeout;
+        let mut backoff = Duration::from_millis(SUBMIT_POLL_INITIAL_BACKOFF_MS);
+        loop {
+            // Read the sequence number before looking the transaction up, so a commit landing
+            // between the two calls is found by the lookup rather than reported as dropped.
+            let on_chain_sequence_number = self.get_sequence_number(address)?;
+            if let Some((txn, _events)) = self.get_txn_by_acc_seq(address, sequence_number, true)? {
+                check_submitted_transaction(&txn, submitted_hash, sequence_number)?;
+                return Ok(txn);
             }
-            Err(e) => {
-                bail!("Transaction submission failed with error: {:?}", e);
+            if on_chain_sequence_number > sequence_number {
+                return Err(Error::TransactionDropped(sequence_number));
             }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::Timeout);
+            }
+            thread::sleep(backoff.min(deadline - now));
+            backoff = (backoff * 2).min(Duration::from_millis(SUBMIT_POLL_MAX_BACKOFF_MS));
         }
     }
 
@@ -208,53 +572,41 @@ impl LibraClient {
         &mut self,
         requested_items: Vec<RequestItem>,
     ) -> Result<UpdateToLatestLedgerResponse> {
+        let method = requested_items
+            .get(0)
+            .map(request_item_method)
+            .ok_or_else(|| {
+                Error::Decode(format_err!("get_with_proof called with no requested items"))
+            })?;
         let req =
             UpdateToLatestLedgerRequest::new(self.trusted_state.latest_version(), requested_items);
 
         debug!("get_with_proof with request: {:?}", req);
-        let proto_req = req.clone().into();
-        let resp = self.client.update_to_latest_ledger(proto_req)?;
-        let resp = UpdateToLatestLedgerResponse::try_from(resp)?;
-
-        match resp.verify(&self.trusted_state, &req)? {
-            TrustedStateChange::Epoch {
-                new_state,
-                latest_epoch_change_li,
-                latest_validator_set,
-                ..
-            } => {
-                info!(
-                    "Verified epoch change to epoch: {}, validator set: [{}]",
-                    latest_epoch_change_li.ledger_info().epoch(),
-                    latest_validator_set
-                );
-                // Update client state
-                self.trusted_state = new_state;
-                self.latest_epoch_change_li = Some(latest_epoch_change_li.clone());
-            }
-            TrustedStateChange::Version { new_state, .. } => {
-                self.trusted_state = new_state;
-            }
+        let payload = hex::encode(lcs::to_bytes(&req).map_err(|e| Error::Decode(e.into()))?);
+        let result = self.json_rpc_client.send_libra_request(
+            method.to_string(),
+            vec![payload],
+            &mut self.chain_id,
+        )?;
+        let resp: UpdateToLatestLedgerResponse =
+            decode_hex_lcs_payload(&result).map_err(Error::Decode)?;
+
+        let (new_state, new_epoch_change_li) =
+            ratchet_trusted_state(&self.trusted_state, &req, &resp)?;
+        self.trusted_state = new_state;
+        if let Some(li) = new_epoch_change_li {
+            self.latest_epoch_change_li = Some(li);
         }
 
         Ok(resp)
     }
 
     fn need_to_retry<T>(try_cnt: u64, ret: &Result<T>) -> bool {
-        if try_cnt >= MAX_GRPC_RETRY_COUNT {
+        if try_cnt >= MAX_RETRY_COUNT {
             return false;
         }
 
-        if let Err(error) = ret {
-            if let Some(grpc_error) = error.downcast_ref::<tonic::Status>() {
-                // Only retry when the connection is down to make sure we won't
-                // send one txn twice.
-                return grpc_error.code() == tonic::Code::Unavailable
-                    || grpc_error.code() == tonic::Code::Unknown;
-            }
-        }
-
-        false
+        matches!(ret, Err(error) if error.is_retriable())
     }
 
     /// LedgerInfo corresponding to the latest epoch change.
@@ -281,7 +633,9 @@ impl LibraClient {
     /// Get the latest account sequence number for the account specified.
     pub fn get_sequence_number(&mut self, address: AccountAddress) -> Result<u64> {
         Ok(match self.get_account_blob(address)?.0 {
-            Some(blob) => AccountResource::try_from(&blob)?.sequence_number(),
+            Some(blob) => AccountResource::try_from(&blob)
+                .map_err(|e| Error::Decode(e.into()))?
+                .sequence_number(),
             None => 0,
         })
     }
@@ -297,7 +651,8 @@ impl LibraClient {
         let account_state_with_proof = response
             .response_items
             .remove(0)
-            .into_get_account_state_response()?;
+            .into_get_account_state_response()
+            .map_err(|e| Error::Decode(e.into()))?;
 
         Ok((
             account_state_with_proof.blob,
@@ -322,7 +677,8 @@ impl LibraClient {
         let (txn_with_proof, _) = response
             .response_items
             .remove(0)
-            .into_get_account_txn_by_seq_num_response()?;
+            .into_get_account_txn_by_seq_num_response()
+            .map_err(|e| Error::Decode(e.into()))?;
 
         Ok(txn_with_proof.map(|t| (t.transaction, t.events)))
     }
@@ -344,7 +700,8 @@ impl LibraClient {
         let txn_list_with_proof = response
             .response_items
             .remove(0)
-            .into_get_transactions_response()?;
+            .into_get_transactions_response()
+            .map_err(|e| Error::Decode(e.into()))?;
 
         // Transform the response.
         let num_txns = txn_list_with_proof.transactions.len();
@@ -380,10 +737,583 @@ impl LibraClient {
                 events_with_proof,
                 proof_of_latest_event,
             } => Ok((events_with_proof, proof_of_latest_event)),
-            _ => bail!(
-                "Incorrect type of response returned: {:?}",
+            _ => Err(Error::Decode(format_err!(
+                "incorrect type of response returned: {:?}",
                 value_with_proof
-            ),
+            ))),
+        }
+    }
+
+    /// Subscribes to events at `access_path`, yielding each verified [`EventWithProof`] as the
+    /// server pushes it, instead of polling [`get_events_by_access_path`](Self::get_events_by_access_path).
+    /// This opens a persistent WebSocket JSON RPC connection; if it drops, the stream
+    /// transparently reconnects and resumes from the last event sequence number it yielded.
+    pub fn subscribe_events(
+        &self,
+        access_path: AccessPath,
+    ) -> impl Stream<Item = Result<EventWithProof>> {
+        let ws_addr = self.json_rpc_client.ws_addr();
+        let mut trusted_state = self.trusted_state.clone();
+
+        try_stream! {
+            let mut next_seq_num = 0u64;
+            loop {
+                let params = vec![
+                    hex::encode(lcs::to_bytes(&access_path).map_err(|e| Error::Decode(e.into()))?),
+                    next_seq_num.to_string(),
+                ];
+                let mut socket = match open_subscription(&ws_addr, "subscribe_events", params).await {
+                    Ok(socket) => socket,
+                    Err(e) if e.is_retriable() => {
+                        tokio::time::sleep(Duration::from_millis(SUBSCRIPTION_RECONNECT_DELAY_MS)).await;
+                        continue;
+                    }
+                    Err(e) => Err(e)?,
+                };
+
+                loop {
+                    let pushed = match next_subscription_item(&mut socket).await {
+                        Ok(Some(value)) => value,
+                        Ok(None) => break, // server closed the socket cleanly; reconnect
+                        Err(e) if e.is_retriable() => break,
+                        Err(e) => Err(e)?,
+                    };
+
+                    // Pushed frames carry a full `UpdateToLatestLedgerResponse`, the same wire
+                    // shape `get_events_by_access_path` polls for, so we ratchet `trusted_state`
+                    // on every frame exactly as the blocking read paths do instead of verifying
+                    // forever against the snapshot taken when the subscription was opened.
+                    let resp: UpdateToLatestLedgerResponse =
+                        decode_hex_lcs_payload(&pushed).map_err(Error::Decode)?;
+                    let req = UpdateToLatestLedgerRequest::new(
+                        trusted_state.latest_version(),
+                        vec![RequestItem::GetEventsByEventAccessPath {
+                            access_path: access_path.clone(),
+                            start_event_seq_num: next_seq_num,
+                            ascending: true,
+                            limit: 1,
+                        }],
+                    );
+                    let (new_state, _) = ratchet_trusted_state(&trusted_state, &req, &resp)?;
+                    trusted_state = new_state;
+
+                    let mut events_with_proof = match resp.response_items.into_iter().next() {
+                        Some(ResponseItem::GetEventsByEventAccessPath {
+                            events_with_proof,
+                            ..
+                        }) => events_with_proof,
+                        other => Err(Error::Decode(format_err!(
+                            "incorrect type of response returned: {:?}",
+                            other
+                        )))?,
+                    };
+                    if events_with_proof.is_empty() {
+                        // The server pushed a frame with nothing new for us yet, e.g. a stale
+                        // epoch-change proof; just wait for the next one.
+                        continue;
+                    }
+                    let event_with_proof = events_with_proof.remove(0);
+
+                    next_seq_num = event_with_proof.event.sequence_number() + 1;
+                    yield event_with_proof;
+                }
+
+                tokio::time::sleep(Duration::from_millis(SUBSCRIPTION_RECONNECT_DELAY_MS)).await;
+            }
+        }
+    }
+
+    /// Subscribes to newly committed transactions starting at `start_version`, yielding each
+    /// verified [`Transaction`] as the server pushes it. Reconnects and resumes from the last
+    /// version it yielded if the underlying WebSocket connection drops.
+    pub fn subscribe_transactions(
+        &self,
+        start_version: Version,
+    ) -> impl Stream<Item = Result<Transaction>> {
+        let ws_addr = self.json_rpc_client.ws_addr();
+        let mut trusted_state = self.trusted_state.clone();
+
+        try_stream! {
+            let mut next_version = start_version;
+            loop {
+                let params = vec![next_version.to_string()];
+                let mut socket =
+                    match open_subscription(&ws_addr, "subscribe_transactions", params).await {
+                        Ok(socket) => socket,
+                        Err(e) if e.is_retriable() => {
+                            tokio::time::sleep(Duration::from_millis(SUBSCRIPTION_RECONNECT_DELAY_MS))
+                                .await;
+                            continue;
+                        }
+                        Err(e) => Err(e)?,
+                    };
+
+                loop {
+                    let pushed = match next_subscription_item(&mut socket).await {
+                        Ok(Some(value)) => value,
+                        Ok(None) => break,
+                        Err(e) if e.is_retriable() => break,
+                        Err(e) => Err(e)?,
+                    };
+
+                    // Pushed frames carry a full `UpdateToLatestLedgerResponse`, the same wire
+                    // shape `get_txn_by_range` polls for, so we ratchet `trusted_state` on every
+                    // frame exactly as the blocking read paths do instead of verifying forever
+                    // against the snapshot taken when the subscription was opened.
+                    let resp: UpdateToLatestLedgerResponse =
+                        decode_hex_lcs_payload(&pushed).map_err(Error::Decode)?;
+                    let req = UpdateToLatestLedgerRequest::new(
+                        trusted_state.latest_version(),
+                        vec![RequestItem::GetTransactions {
+                            start_version: next_version,
+                            limit: 1,
+                            fetch_events: false,
+                        }],
+                    );
+                    let (new_state, _) = ratchet_trusted_state(&trusted_state, &req, &resp)?;
+                    trusted_state = new_state;
+
+                    let txn_list_with_proof = match resp.response_items.into_iter().next() {
+                        Some(item) => item
+                            .into_get_transactions_response()
+                            .map_err(|e| Error::Decode(e.into()))?,
+                        None => Err(Error::Decode(format_err!(
+                            "subscription response carried no response items"
+                        )))?,
+                    };
+                    let transaction = match txn_list_with_proof.transactions.into_iter().next() {
+                        Some(transaction) => transaction,
+                        None => {
+                            // The server pushed a frame with nothing new for us yet, e.g. a
+                            // stale epoch-change proof; just wait for the next one.
+                            continue;
+                        }
+                    };
+
+                    next_version += 1;
+                    yield transaction;
+                }
+
+                tokio::time::sleep(Duration::from_millis(SUBSCRIPTION_RECONNECT_DELAY_MS)).await;
+            }
+        }
+    }
+}
+
+/// Opens a WebSocket JSON RPC connection and sends the initial subscription request.
+async fn open_subscription(
+    ws_addr: &str,
+    method: &str,
+    params: Vec<String>,
+) -> Result<SubscriptionSocket> {
+    use futures::SinkExt;
+
+    let (mut socket, _) = connect_async(ws_addr)
+        .await
+        .map_err(|e| Error::Transport(e.into()))?;
+
+    let id: u64 = rand::thread_rng().gen();
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+        "id": id,
+    });
+    socket
+        .send(Message::Text(request.to_string()))
+        .await
+        .map_err(|e| Error::Transport(e.into()))?;
+
+    Ok(socket)
+}
+
+/// Waits for the next pushed subscription frame and returns its `result` payload, or `None` if
+/// the server closed the connection cleanly.
+async fn next_subscription_item(
+    socket: &mut SubscriptionSocket,
+) -> Result<Option<serde_json::Value>> {
+    use futures::StreamExt;
+
+    loop {
+        let message = match socket.next().await {
+            Some(message) => message.map_err(|e| Error::Transport(e.into()))?,
+            None => return Ok(None),
+        };
+
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => return Ok(None),
+            // Pings/pongs/binary frames carry no subscription data.
+            _ => continue,
+        };
+
+        let data: serde_json::Value =
+            serde_json::from_str(&text).map_err(|e| Error::Decode(e.into()))?;
+        if let Some(error) = data.get("error") {
+            return Err(Error::Decode(format_err!(
+                "subscription error from server: {:?}",
+                error
+            )));
         }
+        if let Some(result) = data.get("result") {
+            return Ok(Some(result.clone()));
+        }
+    }
+}
+
+/// Decodes a hex/LCS-encoded JSON RPC `result` payload, the wire format shared by pushed
+/// subscription frames and the `get_with_proof` family of calls.
+fn decode_hex_lcs_payload<T: serde::de::DeserializeOwned>(
+    value: &serde_json::Value,
+) -> std::result::Result<T, anyhow::Error> {
+    let hex_payload = value
+        .as_str()
+        .ok_or_else(|| format_err!("expected a hex-encoded string payload, got: {:?}", value))?;
+    let bytes = hex::decode(hex_payload)?;
+    lcs::from_bytes(&bytes).map_err(Into::into)
+}
+
+/// An async JSON RPC client built on non-blocking `reqwest`, mirroring [`JsonRpcClient`].
+#[cfg(feature = "async")]
+pub struct AsyncJsonRpcClient {
+    addr: String,
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "async")]
+impl AsyncJsonRpcClient {
+    pub fn new(host: &str, port: u16) -> Self {
+        let addr = format!("http://{}:{}", host, port);
+        let client = reqwest::Client::new();
+
+        Self { client, addr }
+    }
+
+    /// Async counterpart to [`JsonRpcClient::send_libra_request`].
+    pub async fn send_libra_request(
+        &self,
+        method: String,
+        params: Vec<String>,
+        pinned_chain_id: &mut Option<ChainId>,
+    ) -> Result<serde_json::Value> {
+        let id: u64 = rand::thread_rng().gen();
+        let mut request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": id,
+        });
+        if let Some(chain_id) = pinned_chain_id {
+            request["chain_id"] = serde_json::json!(chain_id.id());
+        }
+
+        let response = self.send_with_retry(request).await?;
+
+        let data: serde_json::Value = response.json().await.map_err(|e| Error::Decode(e.into()))?;
+        check_chain_id(&data, pinned_chain_id)?;
+        JsonRpcClient::parse_single_response(&data, id)
+    }
+
+    async fn send_with_retry(&self, request: serde_json::Value) -> Result<reqwest::Response> {
+        let mut response = self.send(&request).await;
+        let mut try_cnt = 0;
+
+        while try_cnt < MAX_RETRY_COUNT && matches!(&response, Err(error) if error.is_retriable()) {
+            response = self.send(&request).await;
+            try_cnt += 1;
+        }
+        response
+    }
+
+    /// Sends `request` and turns a non-2xx HTTP status into `Err(Error::HttpStatus)` here
+    /// (rather than leaving it to the caller), so `send_with_retry`'s retry loop actually sees
+    /// and honors `is_retriable`'s 5xx classification instead of always getting `Ok`.
+    async fn send(&self, request: &serde_json::Value) -> Result<reqwest::Response> {
+        let response = self
+            .client
+            .post(&self.addr)
+            .json(request)
+            .timeout(Duration::from_millis(JSON_RPC_TIMEOUT_MS))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(Error::HttpStatus(response.status().as_u16()));
+        }
+        Ok(response)
+    }
+}
+
+/// An async counterpart to [`LibraClient`], built on non-blocking I/O so async callers don't
+/// need to offload onto a blocking thread pool. Available behind the `async` feature.
+/// Verification runs through the same [`ratchet_trusted_state`] helper as the blocking client,
+/// so the two stay consistent.
+#[cfg(feature = "async")]
+pub struct AsyncLibraClient {
+    async_json_rpc_client: AsyncJsonRpcClient,
+    trusted_state: TrustedState,
+    latest_epoch_change_li: Option<LedgerInfoWithSignatures>,
+    /// The chain id we're pinned to. See [`LibraClient::chain_id`] for details.
+    chain_id: Option<ChainId>,
+}
+
+#[cfg(feature = "async")]
+impl AsyncLibraClient {
+    /// Construct a new async Client instance. If `chain_id` is `None`, the client pins to
+    /// whatever chain id the server reports on its first response instead of checking one up
+    /// front.
+    pub async fn new(
+        host: &str,
+        json_rpc_port: u16,
+        waypoint: Option<Waypoint>,
+        chain_id: Option<ChainId>,
+    ) -> Result<Self> {
+        // If waypoint is present, use it for initial verification, otherwise the initial
+        // verification is essentially empty.
+        let initial_trusted_state = match waypoint {
+            Some(waypoint) => TrustedState::from_waypoint(waypoint),
+            None => TrustedState::new_trust_any_genesis_WARNING_UNSAFE(),
+        };
+        let async_json_rpc_client = AsyncJsonRpcClient::new(host, json_rpc_port);
+        Ok(Self {
+            async_json_rpc_client,
+            trusted_state: initial_trusted_state,
+            latest_epoch_change_li: None,
+            chain_id,
+        })
+    }
+
+    /// Async counterpart to [`LibraClient::submit_transaction`].
+    pub async fn submit_transaction(
+        &mut self,
+        sender_account_opt: Option<&mut AccountData>,
+        transaction: SignedTransaction,
+    ) -> Result<()> {
+        let payload = hex::encode(lcs::to_bytes(&transaction).unwrap());
+        let params = vec![payload];
+
+        let result = self
+            .async_json_rpc_client
+            .send_libra_request("submit".to_string(), params, &mut self.chain_id)
+            .await?;
+        if result != serde_json::Value::Null {
+            return Err(Error::Decode(format_err!(
+                "received unexpected result payload from txn submission: {:?}",
+                result
+            )));
+        }
+        if let Some(sender_account) = sender_account_opt {
+            // Bump up sequence_number if transaction is accepted.
+            sender_account.sequence_number += 1;
+        }
+        Ok(())
+    }
+
+    async fn get_with_proof(
+        &mut self,
+        requested_items: Vec<RequestItem>,
+    ) -> Result<UpdateToLatestLedgerResponse> {
+        let method = requested_items
+            .get(0)
+            .map(request_item_method)
+            .ok_or_else(|| {
+                Error::Decode(format_err!("get_with_proof called with no requested items"))
+            })?;
+        let req =
+            UpdateToLatestLedgerRequest::new(self.trusted_state.latest_version(), requested_items);
+
+        debug!("get_with_proof with request: {:?}", req);
+        let payload = hex::encode(lcs::to_bytes(&req).map_err(|e| Error::Decode(e.into()))?);
+        let result = self
+            .async_json_rpc_client
+            .send_libra_request(method.to_string(), vec![payload], &mut self.chain_id)
+            .await?;
+        let resp: UpdateToLatestLedgerResponse =
+            decode_hex_lcs_payload(&result).map_err(Error::Decode)?;
+
+        let (new_state, new_epoch_change_li) =
+            ratchet_trusted_state(&self.trusted_state, &req, &resp)?;
+        self.trusted_state = new_state;
+        if let Some(li) = new_epoch_change_li {
+            self.latest_epoch_change_li = Some(li);
+        }
+
+        Ok(resp)
+    }
+
+    async fn get_with_proof_retry(
+        &mut self,
+        requested_items: Vec<RequestItem>,
+    ) -> Result<UpdateToLatestLedgerResponse> {
+        let mut resp = self.get_with_proof(requested_items.clone()).await;
+
+        let mut try_cnt = 0;
+        while LibraClient::need_to_retry(try_cnt, &resp) {
+            resp = self.get_with_proof(requested_items.clone()).await;
+            try_cnt += 1;
+        }
+
+        resp
+    }
+
+    /// Async counterpart to [`LibraClient::get_account_blob`].
+    pub async fn get_account_blob(
+        &mut self,
+        address: AccountAddress,
+    ) -> Result<(Option<AccountStateBlob>, Version)> {
+        let req_item = RequestItem::GetAccountState { address };
+
+        let mut response = self.get_with_proof_retry(vec![req_item]).await?;
+        let account_state_with_proof = response
+            .response_items
+            .remove(0)
+            .into_get_account_state_response()
+            .map_err(|e| Error::Decode(e.into()))?;
+
+        Ok((
+            account_state_with_proof.blob,
+            response.ledger_info_with_sigs.ledger_info().version(),
+        ))
+    }
+
+    /// Async counterpart to [`LibraClient::get_txn_by_range`].
+    pub async fn get_txn_by_range(
+        &mut self,
+        start_version: u64,
+        limit: u64,
+        fetch_events: bool,
+    ) -> Result<Vec<(Transaction, Option<Vec<ContractEvent>>)>> {
+        let req_item = RequestItem::GetTransactions {
+            start_version,
+            limit,
+            fetch_events,
+        };
+        let mut response = self.get_with_proof_retry(vec![req_item]).await?;
+        let txn_list_with_proof = response
+            .response_items
+            .remove(0)
+            .into_get_transactions_response()
+            .map_err(|e| Error::Decode(e.into()))?;
+
+        let num_txns = txn_list_with_proof.transactions.len();
+        let event_lists = txn_list_with_proof
+            .events
+            .map(|event_lists| event_lists.into_iter().map(Some).collect())
+            .unwrap_or_else(|| vec![None; num_txns]);
+
+        Ok(itertools::zip_eq(txn_list_with_proof.transactions, event_lists).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libra_crypto::{ed25519::Ed25519PrivateKey, test_utils::TEST_SEED, traits::Uniform};
+    use libra_types::transaction::helpers::get_test_signed_transaction;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn is_retriable_classifies_transient_errors_as_retriable() {
+        assert!(Error::HttpStatus(503).is_retriable());
+        assert!(!Error::HttpStatus(404).is_retriable());
+        assert!(Error::Timeout.is_retriable());
+        assert!(Error::StaleResponse.is_retriable());
+        assert!(Error::Transport(format_err!("connection reset")).is_retriable());
+    }
+
+    #[test]
+    fn is_retriable_classifies_deterministic_errors_as_fatal() {
+        assert!(!Error::JsonRpcError {
+            code: -32000,
+            message: "boom".to_string(),
+        }
+        .is_retriable());
+        assert!(!Error::InvalidProof(format_err!("bad proof")).is_retriable());
+        assert!(!Error::ChainIdMismatch {
+            expected: 1,
+            actual: 2,
+        }
+        .is_retriable());
+        assert!(!Error::TransactionDropped(7).is_retriable());
+        assert!(!Error::Decode(format_err!("bad payload")).is_retriable());
+    }
+
+    fn test_signed_transaction(sequence_number: u64) -> SignedTransaction {
+        let mut rng = StdRng::from_seed(TEST_SEED);
+        let private_key = Ed25519PrivateKey::generate(&mut rng);
+        let public_key = private_key.public_key();
+        get_test_signed_transaction(
+            AccountAddress::random(),
+            sequence_number,
+            private_key,
+            public_key,
+            None,
+            0,
+            0,
+            None,
+        )
+    }
+
+    #[test]
+    fn check_submitted_transaction_confirms_matching_hash() {
+        let submitted = test_signed_transaction(0);
+        let submitted_hash = submitted.hash();
+        let committed = Transaction::UserTransaction(submitted);
+
+        assert!(check_submitted_transaction(&committed, submitted_hash, 0).is_ok());
+    }
+
+    #[test]
+    fn check_submitted_transaction_reports_dropped_on_superseding_transaction() {
+        let submitted = test_signed_transaction(0);
+        let submitted_hash = submitted.hash();
+        // A different transaction occupies the same sequence number slot, e.g. because ours
+        // was superseded before it committed.
+        let superseding = test_signed_transaction(0);
+        let committed = Transaction::UserTransaction(superseding);
+
+        match check_submitted_transaction(&committed, submitted_hash, 0) {
+            Err(Error::TransactionDropped(0)) => (),
+            other => panic!("expected TransactionDropped, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn demux_batch_responses_handles_out_of_order_ids() {
+        let ids = vec![1, 2, 3];
+        let responses = vec![
+            serde_json::json!({"id": 3, "result": "c"}),
+            serde_json::json!({"id": 1, "result": "a"}),
+            serde_json::json!({"id": 2, "result": "b"}),
+        ];
+
+        let demuxed = demux_batch_responses(&ids, &responses);
+        let results: Vec<&str> = demuxed
+            .into_iter()
+            .map(|r| r.unwrap().0.get("result").unwrap().as_str().unwrap())
+            .collect();
+        assert_eq!(results, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn demux_batch_responses_errors_on_missing_id() {
+        let ids = vec![1, 2];
+        let responses = vec![serde_json::json!({"id": 1, "result": "a"})];
+
+        let demuxed = demux_batch_responses(&ids, &responses);
+        assert!(demuxed[0].is_ok());
+        assert!(demuxed[1].is_err());
+    }
+
+    #[test]
+    fn demux_batch_responses_errors_on_duplicate_id() {
+        let ids = vec![1];
+        let responses = vec![
+            serde_json::json!({"id": 1, "result": "a"}),
+            serde_json::json!({"id": 1, "result": "b"}),
+        ];
+
+        let demuxed = demux_batch_responses(&ids, &responses);
+        assert!(demuxed[0].is_err());
     }
 }